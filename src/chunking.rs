@@ -0,0 +1,57 @@
+//! Content-defined chunking for the chunk dedup store.
+//!
+//! Chunk boundaries are declared with a Rabin-style rolling polynomial
+//! hash over a sliding window: whenever the low bits of the hash match a
+//! fixed mask, a boundary is cut. This makes chunk boundaries depend on
+//! local content rather than absolute offset, so inserting or deleting a
+//! few bytes near the start of a file only reshuffles the chunks around
+//! the edit instead of shifting every chunk after it.
+
+/// Sliding window size (in bytes) over which the rolling hash is computed.
+const WINDOW: usize = 48;
+/// Low bits compared against zero to declare a boundary; chosen so the
+/// average chunk size is ~64 KiB (2^16).
+const BOUNDARY_MASK: u64 = (1 << 16) - 1;
+/// Hard lower bound: never cut a chunk smaller than this (except the last).
+const MIN_CHUNK_LEN: usize = 16 * 1024;
+/// Hard upper bound: always cut once a chunk reaches this size.
+const MAX_CHUNK_LEN: usize = 256 * 1024;
+/// Odd multiplier for the rolling polynomial hash.
+const BASE: u64 = 1_099_511_628_211;
+
+/// Split `data` into content-defined chunks, in order, covering all of `data`.
+pub fn chunk_data(data: &[u8]) -> Vec<&[u8]> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    // BASE^(WINDOW - 1) mod 2^64, used to evict the byte leaving the window.
+    let evict_factor = (0..WINDOW - 1).fold(1u64, |acc, _| acc.wrapping_mul(BASE));
+
+    let mut chunks = Vec::new();
+    let mut start = 0usize;
+    let mut hash = 0u64;
+
+    for (i, &byte) in data.iter().enumerate() {
+        hash = hash.wrapping_mul(BASE).wrapping_add(byte as u64);
+        if i >= start + WINDOW {
+            let evicted = data[i - WINDOW] as u64;
+            hash = hash.wrapping_sub(evicted.wrapping_mul(evict_factor).wrapping_mul(BASE));
+        }
+
+        let len = i + 1 - start;
+        let at_mask = len >= MIN_CHUNK_LEN && (hash & BOUNDARY_MASK) == 0;
+        let at_max = len >= MAX_CHUNK_LEN;
+        if at_mask || at_max {
+            chunks.push(&data[start..=i]);
+            start = i + 1;
+            hash = 0;
+        }
+    }
+
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+
+    chunks
+}
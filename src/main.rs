@@ -1,8 +1,11 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
 
-mod ops;
+mod chunking;
 mod fsx;
+mod manifest;
+mod ops;
+mod special;
 
 #[derive(Parser, Debug)]
 #[command(name = "safe_backup_rust", version, about = "Pure Rust backup/restore/delete tool")]
@@ -21,6 +24,10 @@ enum Commands {
     Backup {
         /// Path to the file to backup
         path: std::path::PathBuf,
+
+        /// Skip symlinks, FIFOs, and device nodes (regular files only)
+        #[arg(long)]
+        no_special: bool,
     },
     /// Restore the latest (or specific) backup back to original location
     Restore {
@@ -30,6 +37,26 @@ enum Commands {
         /// Optional backup ID to restore (otherwise latest is used)
         #[arg(long)]
         id: Option<String>,
+
+        /// Overwrite an existing target unconditionally (default)
+        #[arg(long, conflicts_with_all = ["no_clobber", "interactive"])]
+        force: bool,
+
+        /// Abort instead of overwriting an existing target
+        #[arg(long, conflicts_with_all = ["force", "interactive"])]
+        no_clobber: bool,
+
+        /// Prompt on stdin before overwriting an existing target
+        #[arg(long, conflicts_with_all = ["force", "no_clobber"])]
+        interactive: bool,
+
+        /// Copy an existing target to `<path>.~N~` before overwriting it
+        #[arg(long)]
+        backup: bool,
+
+        /// Refuse to recreate symlinks, FIFOs, and device nodes; restoring one is an error (regular files only)
+        #[arg(long)]
+        no_special: bool,
     },
     /// Delete a specific backup by ID
     Delete {
@@ -49,6 +76,34 @@ enum Commands {
         #[arg(long)]
         id: Option<String>,
     },
+    /// Delete any stored chunk that is no longer referenced by a backup
+    Gc,
+    /// Re-check stored checksums for one backup (or all backups of a file)
+    Verify {
+        /// Original path of the file
+        path: std::path::PathBuf,
+
+        /// Optional backup ID to verify (otherwise every backup of `path` is checked)
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// Apply a retention policy to the backups of a file
+    Prune {
+        /// Original path of the file
+        path: std::path::PathBuf,
+
+        /// Always keep the N most recent backups
+        #[arg(long)]
+        keep_last: Option<usize>,
+
+        /// Always keep backups younger than this (e.g. "7d", "12h")
+        #[arg(long)]
+        keep_within: Option<String>,
+
+        /// Only print what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -57,11 +112,25 @@ fn main() -> Result<()> {
     std::fs::create_dir_all(&backup_dir)?;
 
     match cli.command {
-        Commands::Backup { path } => ops::backup(&path, &backup_dir)?,
-        Commands::Restore { path, id } => ops::restore(&path, id.as_deref(), &backup_dir)?,
+        Commands::Backup { path, no_special } => ops::backup(&path, &backup_dir, no_special)?,
+        Commands::Restore { path, id, force: _, no_clobber, interactive, backup, no_special } => {
+            let mode = if no_clobber {
+                ops::OverwriteMode::NoClobber
+            } else if interactive {
+                ops::OverwriteMode::Interactive
+            } else {
+                ops::OverwriteMode::Force
+            };
+            ops::restore(&path, id.as_deref(), &backup_dir, mode, backup, no_special)?
+        }
         Commands::Delete { id } => ops::delete(&id, &backup_dir)?,
         Commands::List { path } => ops::list(&path, &backup_dir)?,
         Commands::View { path, id } => ops::view(&path, id.as_deref(), &backup_dir)?,
+        Commands::Gc => ops::gc(&backup_dir)?,
+        Commands::Verify { path, id } => ops::verify(&path, id.as_deref(), &backup_dir)?,
+        Commands::Prune { path, keep_last, keep_within, dry_run } => {
+            ops::prune(&path, &backup_dir, keep_last, keep_within.as_deref(), dry_run)?
+        }
     }
 
     Ok(())
@@ -0,0 +1,160 @@
+//! On-disk manifest format for the chunked backup store.
+//!
+//! A backup id now names a small JSON manifest instead of a raw copy of
+//! the file: the manifest lists the content-addressed chunks that make
+//! up the original (in order), plus enough metadata to restore and
+//! verify it. The chunk bytes themselves live once under
+//! `backup_dir/chunks/<sha256>`, shared across every manifest that
+//! references them.
+//!
+//! A manifest is either a `File` (one chunk list) or a `Directory` (one
+//! chunk list per relative path under the backed-up tree).
+
+use anyhow::{Context, Result};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::fsx::atomic_write;
+
+/// A single content-addressed chunk referenced by a manifest, in order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChunkRef {
+    pub sha256: String,
+    pub len: u64,
+}
+
+/// Everything needed to restore and verify one backed-up file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub original: PathBuf,
+    pub timestamp: String,
+    /// SHA-256 of the whole reassembled file, for `verify`. Empty for
+    /// non-regular entries (symlinks, FIFOs, devices), which have no content.
+    pub sha256: String,
+    pub chunks: Vec<ChunkRef>,
+    pub metadata: FileMetadata,
+}
+
+impl Manifest {
+    pub fn total_len(&self) -> u64 {
+        self.chunks.iter().map(|c| c.len).sum()
+    }
+}
+
+/// Unix mode bits and timestamps captured alongside each backed-up entry,
+/// plus enough information to recreate non-regular entries on restore.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileMetadata {
+    pub mode: u32,
+    pub mtime: i64,
+    pub atime: i64,
+    pub kind: EntryKind,
+}
+
+/// What kind of filesystem node a manifest entry represents.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EntryKind {
+    Regular,
+    Symlink { target: PathBuf },
+    Fifo,
+    CharDevice { major: u32, minor: u32 },
+    BlockDevice { major: u32, minor: u32 },
+}
+
+/// One file within a directory backup, keyed by its path relative to the
+/// backed-up root.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirEntry {
+    pub relative_path: PathBuf,
+    pub file: Manifest,
+}
+
+/// Everything needed to restore a whole directory tree.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirManifest {
+    pub original: PathBuf,
+    pub timestamp: String,
+    /// Relative paths of every directory under the backed-up root,
+    /// including ones with no files in them, so empty subtrees survive a
+    /// restore. `entries` alone only recreates directories that happen to
+    /// contain a file.
+    #[serde(default)]
+    pub directories: Vec<PathBuf>,
+    pub entries: Vec<DirEntry>,
+}
+
+impl DirManifest {
+    pub fn total_len(&self) -> u64 {
+        self.entries.iter().map(|e| e.file.total_len()).sum()
+    }
+}
+
+/// A manifest is either a single file or a whole directory snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackupManifest {
+    File(Manifest),
+    Directory(DirManifest),
+}
+
+impl BackupManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("reading manifest {}", path.display()))?;
+        serde_json::from_slice(&data).with_context(|| format!("parsing manifest {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        atomic_write(path, &data)
+    }
+
+    /// All chunk hashes referenced anywhere in this manifest.
+    pub fn referenced_chunks(&self) -> Vec<String> {
+        match self {
+            BackupManifest::File(m) => m.chunks.iter().map(|c| c.sha256.clone()).collect(),
+            BackupManifest::Directory(dm) => dm
+                .entries
+                .iter()
+                .flat_map(|e| e.file.chunks.iter().map(|c| c.sha256.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Directory under `backup_dir` where content-addressed chunks live.
+pub fn chunk_store_dir(backup_dir: &Path) -> PathBuf {
+    backup_dir.join("chunks")
+}
+
+pub fn chunk_path(backup_dir: &Path, sha256: &str) -> PathBuf {
+    chunk_store_dir(backup_dir).join(sha256)
+}
+
+/// Write `data` under its content hash unless it's already stored; returns the ref.
+pub fn store_chunk(backup_dir: &Path, data: &[u8]) -> Result<ChunkRef> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    let sha256 = hex::encode(hasher.finalize());
+
+    let path = chunk_path(backup_dir, &sha256);
+    if !path.exists() {
+        atomic_write(&path, data).with_context(|| format!("storing chunk {sha256}"))?;
+    }
+
+    Ok(ChunkRef { sha256, len: data.len() as u64 })
+}
+
+/// Reassemble a file's chunks, in order, into a single buffer.
+pub fn reassemble(backup_dir: &Path, manifest: &Manifest) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(manifest.total_len() as usize);
+    for chunk in &manifest.chunks {
+        let path = chunk_path(backup_dir, &chunk.sha256);
+        let bytes = fs::read(&path).with_context(|| format!("missing chunk {}", chunk.sha256))?;
+        anyhow::ensure!(bytes.len() as u64 == chunk.len, "chunk {} has wrong size on disk", chunk.sha256);
+        data.extend_from_slice(&bytes);
+    }
+    Ok(data)
+}
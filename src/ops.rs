@@ -1,11 +1,15 @@
 use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use fs_err as fs;
 use path_absolutize::Absolutize;
-use crate::fsx::{atomic_copy, atomic_overwrite, ensure_within, read_to_string_lossy};
+use crate::chunking;
+use crate::fsx::{atomic_write, ensure_within, read_to_string_lossy};
+use crate::manifest::{self, BackupManifest, DirEntry, DirManifest, EntryKind, Manifest};
+use crate::special;
 
 
 
@@ -30,46 +34,146 @@ fn is_file(p: &Path) -> bool {
     fs::metadata(p).map(|m| m.is_file()).unwrap_or(false)
 }
 
-pub fn backup(original: &Path, backup_dir: &Path) -> Result<()> {
-    anyhow::ensure!(is_file(original), "Original file does not exist or is not a regular file: {}", original.display());
+fn is_dir(p: &Path) -> bool {
+    fs::metadata(p).map(|m| m.is_dir()).unwrap_or(false)
+}
+
+fn now_rfc3339() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_else(|_| "now".into())
+}
+
+/// Chunk and store one regular file, returning its manifest (not yet saved).
+fn chunk_regular_file(original: &Path, backup_dir: &Path, meta: &std::fs::Metadata) -> Result<Manifest> {
+    let data = fs::read(original).with_context(|| format!("reading {}", original.display()))?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(&data);
+    let sha256 = hex::encode(hasher.finalize());
+
+    let chunks = chunking::chunk_data(&data)
+        .into_iter()
+        .map(|chunk| manifest::store_chunk(backup_dir, chunk))
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("storing chunks for {}", original.display()))?;
+
+    Ok(Manifest {
+        original: original.to_path_buf(),
+        timestamp: now_rfc3339(),
+        sha256,
+        chunks,
+        metadata: special::capture_metadata(meta, EntryKind::Regular),
+    })
+}
+
+/// Capture one filesystem entry (regular file, or, unless `no_special` is
+/// set, a symlink/FIFO/device node) as a manifest. Returns `None` for a
+/// special entry skipped because of `no_special`, or for an entry type we
+/// don't know how to back up (e.g. a Unix socket).
+fn capture_entry(path: &Path, backup_dir: &Path, no_special: bool) -> Result<Option<Manifest>> {
+    let meta = fs::symlink_metadata(path).with_context(|| format!("reading metadata for {}", path.display()))?;
+    let ft = meta.file_type();
+
+    if ft.is_file() {
+        return chunk_regular_file(path, backup_dir, &meta).map(Some);
+    }
+
+    if no_special {
+        return Ok(None);
+    }
+
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    let kind = if ft.is_symlink() {
+        EntryKind::Symlink { target: fs::read_link(path)? }
+    } else if ft.is_fifo() {
+        EntryKind::Fifo
+    } else if ft.is_char_device() || ft.is_block_device() {
+        let rdev = meta.rdev();
+        let major = unsafe { libc::major(rdev) } as u32;
+        let minor = unsafe { libc::minor(rdev) } as u32;
+        if ft.is_char_device() { EntryKind::CharDevice { major, minor } } else { EntryKind::BlockDevice { major, minor } }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(Manifest {
+        original: path.to_path_buf(),
+        timestamp: now_rfc3339(),
+        sha256: String::new(),
+        chunks: Vec::new(),
+        metadata: special::capture_metadata(&meta, kind),
+    }))
+}
+
+pub fn backup(original: &Path, backup_dir: &Path, no_special: bool) -> Result<()> {
     fs::create_dir_all(backup_dir)?;
 
+    if is_dir(original) {
+        return backup_directory(original, backup_dir, no_special);
+    }
+
     let id = make_backup_id(original)?;
     let dest = backup_dir.join(&id);
 
-    atomic_copy(original, &dest).with_context(|| format!("Backing up {} to {}", original.display(), dest.display()))?;
+    let manifest = capture_entry(original, backup_dir, no_special)?.with_context(|| {
+        format!(
+            "{} is a special file skipped by --no-special, or an unsupported file type",
+            original.display()
+        )
+    })?;
+    let size = manifest.total_len();
+    let chunk_count = manifest.chunks.len();
+    let sha256 = manifest.sha256.clone();
+    BackupManifest::File(manifest)
+        .save(&dest)
+        .with_context(|| format!("writing manifest {}", dest.display()))?;
 
-    let checksum = file_sha256(&dest)?;
     println!(
-        "BACKED UP: id={id} path={} size={}B sha256={}",
+        "BACKED UP: id={id} path={} size={size}B sha256={sha256} chunks={chunk_count}",
         dest.display(),
-        fs::metadata(&dest)?.len(),
-        checksum
     );
     Ok(())
 }
 
-pub fn list(original: &Path, backup_dir: &Path) -> Result<()> {
-    let abs_hash_prefix = {
-        let abs = original.absolutize().context("failed to absolutize path")?;
-        let mut hasher = Sha256::new();
-        hasher.update(abs.as_os_str().to_string_lossy().as_bytes());
-        hex::encode(hasher.finalize())
+fn backup_directory(root: &Path, backup_dir: &Path, no_special: bool) -> Result<()> {
+    let id = make_backup_id(root)?;
+    let dest = backup_dir.join(&id);
+
+    let mut entries = Vec::new();
+    let mut directories = Vec::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path == root {
+            continue;
+        }
+        let relative_path = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+
+        if entry.file_type().is_dir() {
+            directories.push(relative_path);
+            continue;
+        }
+        if let Some(file) = capture_entry(path, backup_dir, no_special)? {
+            entries.push(DirEntry { relative_path, file });
+        }
+    }
+
+    let file_count = entries.len();
+    let total_bytes: u64 = entries.iter().map(|e| e.file.total_len()).sum();
+    let dir_manifest = DirManifest {
+        original: root.to_path_buf(),
+        timestamp: now_rfc3339(),
+        directories,
+        entries,
     };
+    BackupManifest::Directory(dir_manifest)
+        .save(&dest)
+        .with_context(|| format!("writing manifest {}", dest.display()))?;
 
-    let mut entries: Vec<PathBuf> = WalkDir::new(backup_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.into_path())
-        .filter(|p| p.file_name()
-            .and_then(|n| n.to_str())
-            .map(|s| s.starts_with(&abs_hash_prefix))
-            .unwrap_or(false)
-        )
-        .collect();
+    println!("BACKED UP DIRECTORY: id={id} path={} files={file_count} size={total_bytes}B", dest.display());
+    Ok(())
+}
 
-    entries.sort();
+pub fn list(original: &Path, backup_dir: &Path) -> Result<()> {
+    let entries = manifest_ids_for(original, None, backup_dir)?;
 
     if entries.is_empty() {
         println!("No backups found for {}", original.display());
@@ -78,19 +182,109 @@ pub fn list(original: &Path, backup_dir: &Path) -> Result<()> {
 
     for p in entries {
         let id = p.file_name().unwrap().to_string_lossy().to_string();
-        let size = fs::metadata(&p)?.len();
-        let sha = file_sha256(&p)?;
-        println!(
-            "id={id} size={}B sha256={sha} backup={} original={}",
-            size,
-            p.display(),
-            original.display()
-        );
+        match BackupManifest::load(&p)? {
+            BackupManifest::File(m) => println!(
+                "id={id} type=file size={}B sha256={} chunks={} backup={} original={}",
+                m.total_len(),
+                m.sha256,
+                m.chunks.len(),
+                p.display(),
+                original.display()
+            ),
+            BackupManifest::Directory(dm) => println!(
+                "id={id} type=directory files={} size={}B backup={} original={}",
+                dm.entries.len(),
+                dm.total_len(),
+                p.display(),
+                original.display()
+            ),
+        }
     }
     Ok(())
 }
 
-pub fn restore(original: &Path, id: Option<&str>, backup_dir: &Path) -> Result<()> {
+/// How `restore` should handle a target path that already has something at
+/// it, modeled after coreutils `mv`'s overwrite flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwriteMode {
+    /// Overwrite unconditionally (today's default behavior).
+    Force,
+    /// Abort instead of overwriting an existing target.
+    NoClobber,
+    /// Ask on stdin before overwriting an existing target.
+    Interactive,
+}
+
+/// Decide whether `target` should be overwritten under `mode`, backing up
+/// the existing file first if `backup_existing` is set. Returns `false` if
+/// the caller should skip writing `target`.
+fn prepare_overwrite(target: &Path, mode: OverwriteMode, backup_existing: bool) -> Result<bool> {
+    if target.exists() {
+        match mode {
+            OverwriteMode::Force => {}
+            OverwriteMode::NoClobber => {
+                println!("SKIPPED (exists, --no-clobber): {}", target.display());
+                return Ok(false);
+            }
+            OverwriteMode::Interactive => {
+                print!("overwrite {}? (y/N) ", target.display());
+                io::stdout().flush().ok();
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    println!("SKIPPED (declined): {}", target.display());
+                    return Ok(false);
+                }
+            }
+        }
+
+        if backup_existing {
+            let numbered = numbered_backup_path(target);
+            fs::copy(target, &numbered).with_context(|| format!("backing up existing {}", target.display()))?;
+            println!("BACKED UP EXISTING: {} -> {}", target.display(), numbered.display());
+        }
+    }
+
+    Ok(true)
+}
+
+/// First unused `<path>.~N~` sibling of `target`, numbering from 1.
+fn numbered_backup_path(target: &Path) -> PathBuf {
+    let name = target.file_name().and_then(|s| s.to_str()).unwrap_or("file");
+    let mut n = 1u32;
+    loop {
+        let candidate = target.with_file_name(format!("{name}.~{n}~"));
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Restore one manifest entry to `dest`, honoring the overwrite mode and
+/// (unless `no_special`) recreating symlinks/FIFOs/device nodes. Returns
+/// `false` if the entry was skipped by the overwrite mode.
+fn restore_entry(dest: &Path, manifest: &Manifest, backup_dir: &Path, mode: OverwriteMode, backup_existing: bool, no_special: bool) -> Result<bool> {
+    if !prepare_overwrite(dest, mode, backup_existing)? {
+        return Ok(false);
+    }
+
+    match &manifest.metadata.kind {
+        EntryKind::Regular => {
+            let data = manifest::reassemble(backup_dir, manifest)?;
+            atomic_write(dest, &data)?;
+        }
+        other if no_special => {
+            anyhow::bail!("{} is a special file ({other:?}); restore without --no-special to recreate it", dest.display());
+        }
+        other => special::create_node(dest, other)?,
+    }
+
+    special::apply_metadata(dest, &manifest.metadata)?;
+    Ok(true)
+}
+
+pub fn restore(original: &Path, id: Option<&str>, backup_dir: &Path, mode: OverwriteMode, backup_existing: bool, no_special: bool) -> Result<()> {
     fs::create_dir_all(backup_dir)?;
 
     let candidate = match id {
@@ -104,10 +298,33 @@ pub fn restore(original: &Path, id: Option<&str>, backup_dir: &Path) -> Result<(
     let _target_dir = original.parent().unwrap_or_else(|| Path::new("."));
     ensure_within(&candidate, backup_dir).context("Backup file must be within the backup directory")?;
 
-    // Atomic write via temp file then rename
-    atomic_overwrite(&candidate, original)?;
+    match BackupManifest::load(&candidate)? {
+        BackupManifest::File(manifest) => {
+            if restore_entry(original, &manifest, backup_dir, mode, backup_existing, no_special)? {
+                println!("RESTORED: {} <- {} ({} chunks)", original.display(), candidate.display(), manifest.chunks.len());
+            }
+        }
+        BackupManifest::Directory(dir_manifest) => {
+            fs::create_dir_all(original)?;
+            for relative_path in &dir_manifest.directories {
+                fs::create_dir_all(original.join(relative_path))?;
+            }
 
-    println!("RESTORED: {} <- {}", original.display(), candidate.display());
+            let mut restored = 0usize;
+            for entry in &dir_manifest.entries {
+                let dest = original.join(&entry.relative_path);
+                if restore_entry(&dest, &entry.file, backup_dir, mode, backup_existing, no_special)? {
+                    restored += 1;
+                }
+            }
+            println!(
+                "RESTORED DIRECTORY: {} <- {} ({restored}/{} files)",
+                original.display(),
+                candidate.display(),
+                dir_manifest.entries.len()
+            );
+        }
+    }
     Ok(())
 }
 
@@ -119,23 +336,40 @@ pub fn delete(id: &str, backup_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-fn latest_backup_for(original: &Path, backup_dir: &Path) -> Result<Option<PathBuf>> {
-    let mut matches: Vec<PathBuf> = vec![];
-    let abs = original.absolutize().context("failed to absolutize path")?;
-    let mut hasher = Sha256::new();
-    hasher.update(abs.as_os_str().to_string_lossy().as_bytes());
-    let prefix = hex::encode(hasher.finalize());
+/// Remove any stored chunk that isn't referenced by a live manifest.
+pub fn gc(backup_dir: &Path) -> Result<()> {
+    let mut live = std::collections::HashSet::new();
+    for entry in WalkDir::new(backup_dir).max_depth(1).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if let Ok(manifest) = BackupManifest::load(entry.path()) {
+            live.extend(manifest.referenced_chunks());
+        }
+    }
 
-    for entry in fs::read_dir(backup_dir)? {
-        let entry = entry?;
-        if !entry.file_type()?.is_file() { continue; }
-        let name = entry.file_name().to_string_lossy().to_string();
-        if name.starts_with(&prefix) {
-            matches.push(entry.path());
+    let chunk_dir = manifest::chunk_store_dir(backup_dir);
+    let mut removed = 0u64;
+    if chunk_dir.exists() {
+        for entry in fs::read_dir(&chunk_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !live.contains(&name) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
         }
     }
-    matches.sort();
-    Ok(matches.pop())
+
+    println!("GC: removed {removed} unreferenced chunk(s), {} chunk(s) still live", live.len());
+    Ok(())
+}
+
+fn latest_backup_for(original: &Path, backup_dir: &Path) -> Result<Option<PathBuf>> {
+    Ok(manifest_ids_for(original, None, backup_dir)?.pop())
 }
 
 fn file_sha256(path: &Path) -> Result<String> {
@@ -151,17 +385,201 @@ fn file_sha256(path: &Path) -> Result<String> {
     Ok(hex::encode(hasher.finalize()))
 }
 
+/// All backup manifest ids for `original`, sorted, or just `[id]` if given.
+fn manifest_ids_for(original: &Path, id: Option<&str>, backup_dir: &Path) -> Result<Vec<PathBuf>> {
+    if let Some(id) = id {
+        return Ok(vec![backup_dir.join(id)]);
+    }
+
+    let abs = original.absolutize().context("failed to absolutize path")?;
+    let mut hasher = Sha256::new();
+    hasher.update(abs.as_os_str().to_string_lossy().as_bytes());
+    let prefix = hex::encode(hasher.finalize());
+
+    let mut matches: Vec<PathBuf> = WalkDir::new(backup_dir)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).map(|s| s.starts_with(&prefix)).unwrap_or(false))
+        .collect();
+    matches.sort();
+    Ok(matches)
+}
+
+/// Re-hash one file manifest's stored chunks and whole-file checksum
+/// against what was recorded at backup time.
+fn verify_file_manifest(m: &Manifest, backup_dir: &Path) -> Result<()> {
+    if m.metadata.kind != EntryKind::Regular {
+        return Ok(());
+    }
+
+    for chunk in &m.chunks {
+        let path = manifest::chunk_path(backup_dir, &chunk.sha256);
+        let actual = file_sha256(&path).with_context(|| format!("reading chunk {}", chunk.sha256))?;
+        anyhow::ensure!(actual == chunk.sha256, "chunk {} is corrupt (hashes to {actual})", chunk.sha256);
+    }
+
+    let data = manifest::reassemble(backup_dir, m)?;
+    let mut hasher = Sha256::new();
+    hasher.update(&data);
+    let whole = hex::encode(hasher.finalize());
+    anyhow::ensure!(whole == m.sha256, "whole-file checksum mismatch: recorded {}, reassembled {whole}", m.sha256);
+    Ok(())
+}
+
+fn verify_manifest(manifest_path: &Path, backup_dir: &Path) -> Result<()> {
+    match BackupManifest::load(manifest_path)? {
+        BackupManifest::File(m) => verify_file_manifest(&m, backup_dir),
+        BackupManifest::Directory(dm) => {
+            for entry in &dm.entries {
+                verify_file_manifest(&entry.file, backup_dir)
+                    .with_context(|| format!("entry {}", entry.relative_path.display()))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Re-check stored checksums for one backup (or all backups of `original`).
+pub fn verify(original: &Path, id: Option<&str>, backup_dir: &Path) -> Result<()> {
+    let targets = manifest_ids_for(original, id, backup_dir)?;
+    anyhow::ensure!(!targets.is_empty(), "No backups found for {}", original.display());
+
+    let mut failed = 0usize;
+    for p in &targets {
+        let backup_id = p.file_name().unwrap().to_string_lossy().to_string();
+        match verify_manifest(p, backup_dir) {
+            Ok(()) => println!("OK: {backup_id}"),
+            Err(e) => {
+                println!("FAILED: {backup_id} ({e})");
+                failed += 1;
+            }
+        }
+    }
+
+    anyhow::ensure!(failed == 0, "{failed} of {} backup(s) failed verification", targets.len());
+    Ok(())
+}
+
 pub fn view(original: &Path, id: Option<&str>, backup_dir: &Path) -> Result<()> {
-    let target_path = match id {
-        Some(id) => backup_dir.join(id),
-        None => original.to_path_buf(),
+    let (target_path, contents) = match id {
+        Some(id) => {
+            let manifest_path = backup_dir.join(id);
+            anyhow::ensure!(is_file(&manifest_path), "File not found: {}", manifest_path.display());
+            let manifest = match BackupManifest::load(&manifest_path)? {
+                BackupManifest::File(m) => m,
+                BackupManifest::Directory(_) => anyhow::bail!("id {id} is a directory backup; use `restore` instead of `view`"),
+            };
+            anyhow::ensure!(
+                manifest.metadata.kind == EntryKind::Regular,
+                "id {id} is a special file ({:?}); use `restore` instead of `view`",
+                manifest.metadata.kind
+            );
+            let data = manifest::reassemble(backup_dir, &manifest)?;
+            (manifest_path, String::from_utf8_lossy(&data).to_string())
+        }
+        None => {
+            anyhow::ensure!(fs::metadata(original)?.is_file(), "File not found: {}", original.display());
+            (original.to_path_buf(), read_to_string_lossy(original)?)
+        }
     };
 
-    anyhow::ensure!(fs::metadata(&target_path)?.is_file(), "File not found: {}", target_path.display());
-
-    let contents = read_to_string_lossy(&target_path)?;
     println!("--- BEGIN CONTENTS ({}) ---", target_path.display());
     println!("{}", contents);
     println!("--- END CONTENTS ---");
     Ok(())
 }
+
+/// Parse a coarse duration like "7d", "12h", "30m" (s/m/h/d/w units).
+fn parse_duration(raw: &str) -> Result<time::Duration> {
+    let raw = raw.trim();
+    anyhow::ensure!(raw.len() >= 2, "invalid duration '{raw}' (expected e.g. '7d', '12h')");
+    let (num, unit) = raw.split_at(raw.len() - 1);
+    let n: i64 = num.parse().with_context(|| format!("invalid duration '{raw}'"))?;
+    let seconds = match unit {
+        "s" => n,
+        "m" => n * 60,
+        "h" => n * 3_600,
+        "d" => n * 86_400,
+        "w" => n * 86_400 * 7,
+        other => anyhow::bail!("unknown duration unit '{other}' in '{raw}' (expected one of s/m/h/d/w)"),
+    };
+    Ok(time::Duration::seconds(seconds))
+}
+
+/// The RFC3339 timestamp embedded in a backup id
+/// (`<hash>__<timestamp>__<basename>`).
+fn id_timestamp(manifest_path: &Path) -> Result<OffsetDateTime> {
+    let name = manifest_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .with_context(|| format!("backup id {} is not valid UTF-8", manifest_path.display()))?;
+    let ts = name
+        .splitn(3, "__")
+        .nth(1)
+        .with_context(|| format!("backup id '{name}' does not match <hash>__<timestamp>__<basename>"))?;
+    OffsetDateTime::parse(ts, &Rfc3339).with_context(|| format!("backup id '{name}' has an unparseable timestamp"))
+}
+
+/// Apply `--keep-last`/`--keep-within` retention to the backups of
+/// `original`, deleting (or, with `dry_run`, just logging) the losers.
+pub fn prune(
+    original: &Path,
+    backup_dir: &Path,
+    keep_last: Option<usize>,
+    keep_within: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    anyhow::ensure!(
+        keep_last.is_some() || keep_within.is_some(),
+        "prune requires --keep-last and/or --keep-within"
+    );
+
+    let mut entries: Vec<(PathBuf, OffsetDateTime)> = manifest_ids_for(original, None, backup_dir)?
+        .into_iter()
+        .map(|p| {
+            let ts = id_timestamp(&p)?;
+            Ok((p, ts))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|(_, ts)| *ts);
+
+    let mut keep = vec![false; entries.len()];
+
+    if let Some(n) = keep_last {
+        let start = entries.len().saturating_sub(n);
+        keep[start..].fill(true);
+    }
+
+    if let Some(raw) = keep_within {
+        let window = parse_duration(raw)?;
+        let cutoff = OffsetDateTime::now_utc() - window;
+        for (keep, (_, ts)) in keep.iter_mut().zip(&entries) {
+            if *ts >= cutoff {
+                *keep = true;
+            }
+        }
+    }
+
+    let mut kept = 0usize;
+    let mut deleted = 0usize;
+    for (keep, (path, _)) in keep.iter().zip(&entries) {
+        let id = path.file_name().unwrap().to_string_lossy().to_string();
+        if *keep {
+            kept += 1;
+            continue;
+        }
+        if dry_run {
+            println!("WOULD DELETE: {id}");
+        } else {
+            fs::remove_file(path)?;
+            println!("DELETED: {id}");
+        }
+        deleted += 1;
+    }
+
+    println!("PRUNE: kept {kept}, {} {deleted}{}", if dry_run { "would delete" } else { "deleted" }, if dry_run { " (dry run)" } else { "" });
+    Ok(())
+}
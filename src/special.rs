@@ -0,0 +1,89 @@
+//! Unix node creation and metadata restore for backup entries that are
+//! more than a plain regular file: symlinks, FIFOs, and block/char device
+//! nodes (see `manifest::EntryKind`). Regular-file content is handled by
+//! chunk reassembly elsewhere; this module only recreates the node itself
+//! and reapplies captured mode bits and timestamps.
+
+use anyhow::{Context, Result};
+use fs_err as fs;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+use crate::manifest::{EntryKind, FileMetadata};
+
+/// Capture the Unix mode bits and timestamps of `meta` alongside `kind`.
+pub fn capture_metadata(meta: &std::fs::Metadata, kind: EntryKind) -> FileMetadata {
+    FileMetadata { mode: meta.mode(), mtime: meta.mtime(), atime: meta.atime(), kind }
+}
+
+/// Recreate the non-regular node described by `kind` at `path`.
+pub fn create_node(path: &Path, kind: &EntryKind) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(path);
+
+    match kind {
+        EntryKind::Regular => anyhow::bail!("create_node called for a regular file: {}", path.display()),
+        EntryKind::Symlink { target } => std::os::unix::fs::symlink(target, path)
+            .with_context(|| format!("creating symlink {}", path.display()))?,
+        EntryKind::Fifo => mknod(path, libc::S_IFIFO, 0)?,
+        EntryKind::CharDevice { major, minor } => {
+            mknod(path, libc::S_IFCHR, unsafe { libc::makedev(*major, *minor) })?
+        }
+        EntryKind::BlockDevice { major, minor } => {
+            mknod(path, libc::S_IFBLK, unsafe { libc::makedev(*major, *minor) })?
+        }
+    }
+    Ok(())
+}
+
+fn mknod(path: &Path, node_type: libc::mode_t, dev: libc::dev_t) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("path {} contains a NUL byte", path.display()))?;
+    let mode = node_type | 0o600;
+    let rc = unsafe { libc::mknod(c_path.as_ptr(), mode, dev) };
+    anyhow::ensure!(rc == 0, "mknod failed for {}: {}", path.display(), std::io::Error::last_os_error());
+    Ok(())
+}
+
+/// Reapply captured Unix mode bits and mtime/atime to `path`.
+///
+/// Symlinks are special-cased: `chmod`/`utimes` both follow the link, so
+/// applying them here would instead clobber whatever the link happens to
+/// point at (or fail with ENOENT for a dangling link). POSIX has no
+/// portable `lchmod`, so a restored symlink simply keeps the mode it's
+/// created with; we still restore its own timestamps, via `utimensat`
+/// with `AT_SYMLINK_NOFOLLOW` so the link itself (not its target) is touched.
+pub fn apply_metadata(path: &Path, metadata: &FileMetadata) -> Result<()> {
+    if matches!(metadata.kind, EntryKind::Symlink { .. }) {
+        return apply_symlink_times(path, metadata);
+    }
+
+    fs::set_permissions(path, std::fs::Permissions::from_mode(metadata.mode))
+        .with_context(|| format!("setting mode on {}", path.display()))?;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("path {} contains a NUL byte", path.display()))?;
+    let times = [
+        libc::timeval { tv_sec: metadata.atime as libc::time_t, tv_usec: 0 },
+        libc::timeval { tv_sec: metadata.mtime as libc::time_t, tv_usec: 0 },
+    ];
+    let rc = unsafe { libc::utimes(c_path.as_ptr(), times.as_ptr()) };
+    anyhow::ensure!(rc == 0, "setting times on {}: {}", path.display(), std::io::Error::last_os_error());
+    Ok(())
+}
+
+fn apply_symlink_times(path: &Path, metadata: &FileMetadata) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("path {} contains a NUL byte", path.display()))?;
+    let times = [
+        libc::timespec { tv_sec: metadata.atime as libc::time_t, tv_nsec: 0 },
+        libc::timespec { tv_sec: metadata.mtime as libc::time_t, tv_nsec: 0 },
+    ];
+    let rc = unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) };
+    anyhow::ensure!(rc == 0, "setting times on symlink {}: {}", path.display(), std::io::Error::last_os_error());
+    Ok(())
+}
@@ -2,6 +2,7 @@ use assert_cmd::prelude::*; // Add methods on commands
 use predicates::prelude::*;
 use std::process::Command;
 use std::fs;
+use std::io::Write;
 
 #[test]
 fn backup_and_restore_cycle() -> Result<(), Box<dyn std::error::Error>> {
@@ -26,3 +27,290 @@ fn backup_and_restore_cycle() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn repeated_backups_share_chunks_on_disk() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let original = tmp.path().join("slowly_changing.txt");
+    let backup_dir = tmp.path().join("backups");
+
+    let mut f = fs::File::create(&original)?;
+    writeln!(f, "{}", "x".repeat(20_000))?;
+    drop(f);
+
+    let chunk_dir = backup_dir.join("chunks");
+    let mut chunk_count_after_first = None;
+    for _ in 0..3 {
+        Command::cargo_bin("safe_backup_rust")?
+            .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", original.to_str().unwrap()])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("chunks="));
+        if chunk_count_after_first.is_none() {
+            chunk_count_after_first = Some(fs::read_dir(&chunk_dir)?.count());
+        }
+    }
+
+    let chunk_count = fs::read_dir(&chunk_dir)?.count();
+    // Three backups of an unchanged file should collapse onto the same chunk set.
+    assert!(chunk_count >= 1, "expected at least one stored chunk");
+    assert_eq!(Some(chunk_count), chunk_count_after_first, "later backups of an unchanged file must not store new chunks");
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "gc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("removed 0"));
+
+    let chunk_count_after_gc = fs::read_dir(&chunk_dir)?.count();
+    assert_eq!(chunk_count, chunk_count_after_gc, "gc should not remove chunks still referenced by manifests");
+
+    Ok(())
+}
+
+#[test]
+fn directory_backup_and_restore_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let project = tmp.path().join("project");
+    let backup_dir = tmp.path().join("backups");
+
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(project.join("README.md"), "hello")?;
+    fs::write(project.join("src").join("main.rs"), "fn main() {}")?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", project.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BACKED UP DIRECTORY").and(predicate::str::contains("files=2")));
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "list", project.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("type=directory"));
+
+    fs::remove_file(project.join("README.md"))?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "restore", project.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RESTORED DIRECTORY"));
+
+    assert_eq!(fs::read_to_string(project.join("README.md"))?, "hello");
+    assert_eq!(fs::read_to_string(project.join("src").join("main.rs"))?, "fn main() {}");
+
+    Ok(())
+}
+
+#[test]
+fn directory_backup_preserves_empty_subdirectories() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let project = tmp.path().join("project");
+    let backup_dir = tmp.path().join("backups");
+
+    fs::create_dir_all(project.join("src"))?;
+    fs::create_dir_all(project.join("empty_dir"))?;
+    fs::write(project.join("src").join("main.rs"), "fn main() {}")?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", project.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("files=1"));
+
+    fs::remove_dir_all(&project)?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "restore", project.to_str().unwrap()])
+        .assert()
+        .success();
+
+    assert!(project.join("empty_dir").is_dir());
+    assert_eq!(fs::read_to_string(project.join("src").join("main.rs"))?, "fn main() {}");
+
+    Ok(())
+}
+
+#[test]
+fn restore_no_clobber_leaves_existing_target_untouched() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let original = tmp.path().join("data.txt");
+    let backup_dir = tmp.path().join("backups");
+
+    fs::write(&original, "original contents")?;
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", original.to_str().unwrap()])
+        .assert()
+        .success();
+
+    fs::write(&original, "changed since backup")?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "restore", "--no-clobber", original.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("SKIPPED"));
+
+    assert_eq!(fs::read_to_string(&original)?, "changed since backup");
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "restore", "--backup", original.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BACKED UP EXISTING"));
+
+    assert_eq!(fs::read_to_string(&original)?, "original contents");
+    assert_eq!(fs::read_to_string(original.with_file_name("data.txt.~1~"))?, "changed since backup");
+
+    Ok(())
+}
+
+#[test]
+fn directory_backup_preserves_symlinks_unless_no_special() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let project = tmp.path().join("project");
+    let backup_dir = tmp.path().join("backups");
+
+    fs::create_dir_all(&project)?;
+    fs::write(project.join("real.txt"), "real contents")?;
+    std::os::unix::fs::symlink("real.txt", project.join("link.txt"))?;
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(project.join("real.txt"), std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", project.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("files=2"));
+
+    fs::remove_file(project.join("link.txt"))?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "restore", project.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let restored = fs::symlink_metadata(project.join("link.txt"))?;
+    assert!(restored.file_type().is_symlink());
+    assert_eq!(fs::read_link(project.join("link.txt"))?, std::path::Path::new("real.txt"));
+
+    // Restoring the symlink's own timestamps must not touch the mode of
+    // whatever it points at.
+    use std::os::unix::fs::PermissionsExt;
+    let real_mode = fs::metadata(project.join("real.txt"))?.permissions().mode() & 0o777;
+    assert_eq!(real_mode, 0o600, "restoring link.txt must not chmod its target real.txt");
+
+    // With --no-special the symlink is left out of the backup.
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", "--no-special", project.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("files=1"));
+
+    Ok(())
+}
+
+#[test]
+fn restore_dangling_symlink_does_not_error() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let project = tmp.path().join("project");
+    let backup_dir = tmp.path().join("backups");
+
+    fs::create_dir_all(&project)?;
+    std::os::unix::fs::symlink("missing_target.txt", project.join("dangling.txt"))?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", project.to_str().unwrap()])
+        .assert()
+        .success();
+
+    fs::remove_file(project.join("dangling.txt"))?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "restore", project.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let restored = fs::symlink_metadata(project.join("dangling.txt"))?;
+    assert!(restored.file_type().is_symlink());
+    assert_eq!(fs::read_link(project.join("dangling.txt"))?, std::path::Path::new("missing_target.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn verify_detects_corrupted_chunk() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let original = tmp.path().join("data.txt");
+    let backup_dir = tmp.path().join("backups");
+
+    fs::write(&original, "some important contents")?;
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", original.to_str().unwrap()])
+        .assert()
+        .success();
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "verify", original.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK:"));
+
+    let chunk_dir = backup_dir.join("chunks");
+    let chunk_file = fs::read_dir(&chunk_dir)?.next().unwrap()?.path();
+    fs::write(&chunk_file, "corrupted bytes")?;
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "verify", original.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("FAILED:"));
+
+    Ok(())
+}
+
+fn manifest_file_count(backup_dir: &std::path::Path) -> usize {
+    fs::read_dir(backup_dir)
+        .map(|rd| {
+            rd.filter_map(|e| e.ok())
+                .filter(|e| e.path().is_file())
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+#[test]
+fn prune_keep_last_removes_older_backups() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    let original = tmp.path().join("data.txt");
+    let backup_dir = tmp.path().join("backups");
+
+    for i in 0..3 {
+        fs::write(&original, format!("contents {i}"))?;
+        Command::cargo_bin("safe_backup_rust")?
+            .args(["--backup-dir", backup_dir.to_str().unwrap(), "backup", original.to_str().unwrap()])
+            .assert()
+            .success();
+    }
+    assert_eq!(manifest_file_count(&backup_dir), 3);
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "prune", "--keep-last", "2", "--dry-run", original.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("WOULD DELETE"));
+    assert_eq!(manifest_file_count(&backup_dir), 3, "dry-run must not delete anything");
+
+    Command::cargo_bin("safe_backup_rust")?
+        .args(["--backup-dir", backup_dir.to_str().unwrap(), "prune", "--keep-last", "2", original.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("DELETED"));
+    assert_eq!(manifest_file_count(&backup_dir), 2);
+
+    Ok(())
+}